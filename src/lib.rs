@@ -1,4 +1,169 @@
-use std::io::{self, BufRead, BufReader, Read, Result, Write};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// `--no-default-features` gets this crate off `std`, but not off an
+// allocator: `Tokens`'s state stack, `format_json`/`format_json_minify`'s
+// frame stack, and their per-token scratch buffer all grow with input
+// (nesting depth, key/string/number length), and clamping any of them to a
+// fixed capacity would silently truncate real content rather than merely
+// shorten cosmetic whitespace the way `Indent` does. So a `no_std` target
+// here still needs a `#[global_allocator]` (e.g. `embedded-alloc`); this is
+// no_std-with-alloc, not the fully allocator-free embedded story.
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+use no_std_io::{self as io, BufRead, Read, Result, Write};
+
+// `core_io`, which the no_std build originally depended on, mirrors
+// nightly-only internal `std::io` plumbing and hasn't built against any
+// stable or nightly compiler available to us since its last release in
+// 2021 (E0554/E0557 removed `#![feature(..)]` gates, `MaybeUninit::get_mut`);
+// its closest maintained successor, `core2`, has had every published
+// version yanked from the registry. Rather than pin a dependency that
+// cannot compile, this reimplements the small slice of `Read`/`BufRead`/
+// `Write` that `format_json`/`format_json_fast`/`Tokens` actually call, so
+// the no_std build has no external crate to rot out from under it.
+#[cfg(not(feature = "std"))]
+mod no_std_io {
+    use core::fmt;
+
+    #[derive(Debug)]
+    pub struct Error(&'static str);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        Other,
+        UnexpectedEof,
+    }
+
+    impl Error {
+        pub fn other(msg: &'static str) -> Error {
+            Error(msg)
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            match kind {
+                ErrorKind::Other => Error("other error"),
+                ErrorKind::UnexpectedEof => Error("unexpected end of file"),
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub trait BufRead: Read {
+        fn fill_buf(&mut self) -> Result<&[u8]>;
+        fn consume(&mut self, amt: usize);
+    }
+
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+        fn flush(&mut self) -> Result<()>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(Error("failed to write whole buffer")),
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+            struct Adapter<'a, W: Write + ?Sized> {
+                inner: &'a mut W,
+                error: Result<()>,
+            }
+
+            impl<'a, W: Write + ?Sized> fmt::Write for Adapter<'a, W> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    self.inner.write_all(s.as_bytes()).map_err(|e| {
+                        self.error = Err(e);
+                        fmt::Error
+                    })
+                }
+            }
+
+            let mut adapter = Adapter {
+                inner: self,
+                error: Ok(()),
+            };
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                Err(..) => adapter.error.and(Err(Error("formatter error"))),
+            }
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<R: BufRead + ?Sized> BufRead for &mut R {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            (**self).fill_buf()
+        }
+
+        fn consume(&mut self, amt: usize) {
+            (**self).consume(amt)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for alloc::vec::Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum State {
@@ -9,20 +174,56 @@ enum State {
     Elem,
 }
 
+#[cfg(feature = "std")]
 fn error_msg(msg: String) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, msg)
+    io::Error::other(msg)
+}
+
+#[cfg(feature = "std")]
+fn unexpected(c: u8) -> io::Error {
+    error_msg(format!("unexpected input: '{}'", c as char))
+}
+
+#[cfg(feature = "std")]
+fn invalid(c: u8) -> io::Error {
+    error_msg(format!("invalid input: {}", c as char))
+}
+
+#[cfg(not(feature = "std"))]
+fn error_msg(msg: &'static str) -> io::Error {
+    io::Error::other(msg)
+}
+
+#[cfg(not(feature = "std"))]
+fn unexpected(_c: u8) -> io::Error {
+    error_msg("unexpected input")
+}
+
+#[cfg(not(feature = "std"))]
+fn invalid(_c: u8) -> io::Error {
+    error_msg("invalid input")
+}
+
+#[cfg(feature = "std")]
+fn unpaired_surrogate() -> io::Error {
+    error_msg("unpaired UTF-16 surrogate in \\u escape".to_string())
+}
+
+#[cfg(not(feature = "std"))]
+fn unpaired_surrogate() -> io::Error {
+    error_msg("unpaired UTF-16 surrogate in \\u escape")
 }
 
 const BAD_CHAR: u8 = b'\x00';
 
-fn peek_char(r: &mut BufReader<&mut dyn Read>) -> u8 {
+fn peek_char<R: BufRead + ?Sized>(r: &mut R) -> u8 {
     if let Ok(b) = r.fill_buf() {
-        return *b.get(0).unwrap_or(&BAD_CHAR);
+        return *b.first().unwrap_or(&BAD_CHAR);
     }
     BAD_CHAR
 }
 
-fn skip_whitespace(br: &mut BufReader<&mut dyn Read>) -> Result<()> {
+fn skip_whitespace<R: BufRead + ?Sized>(br: &mut R) -> Result<()> {
     loop {
         let buf = br.fill_buf()?;
         let mut i = 0usize;
@@ -37,37 +238,140 @@ fn skip_whitespace(br: &mut BufReader<&mut dyn Read>) -> Result<()> {
     }
 }
 
-fn write_string(w: &mut dyn Write, br: &mut BufReader<&mut dyn Read>) -> Result<()> {
-    let mut buf = br.fill_buf()?;
-    assert!(buf[0] == b'"');
-    let mut i = 1usize;
-    let mut prev = BAD_CHAR;
-    loop {
-        if i >= buf.len() {
-            w.write_all(buf)?;
-            br.consume(i);
-            buf = br.fill_buf()?;
-            if buf.is_empty() {
-                return Err(io::ErrorKind::UnexpectedEof.into());
+fn read_byte<R: BufRead + ?Sized>(br: &mut R) -> Result<u8> {
+    let buf = br.fill_buf()?;
+    if buf.is_empty() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let b = buf[0];
+    br.consume(1);
+    Ok(b)
+}
+
+fn read_hex4<R: BufRead + ?Sized>(br: &mut R) -> Result<u32> {
+    let mut v = 0u32;
+    for _ in 0..4 {
+        let c = read_byte(br)?;
+        let d = (c as char).to_digit(16).ok_or_else(|| invalid(c))?;
+        v = (v << 4) | d;
+    }
+    Ok(v)
+}
+
+fn utf8_len(first: u8) -> Option<usize> {
+    match first {
+        0x00..=0x7f => Some(1),
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+// Reads the continuation bytes of a multi-byte UTF-8 sequence whose lead
+// byte was `first`, validating each one, and decodes the resulting scalar
+// value. This is what guarantees the literal (non-escaped) bytes of a
+// string are well-formed UTF-8 rather than being copied through blindly.
+fn read_utf8_char<R: BufRead + ?Sized>(br: &mut R, first: u8) -> Result<char> {
+    let len = utf8_len(first).ok_or_else(|| invalid(first))?;
+    let mut bytes = [0u8; 4];
+    bytes[0] = first;
+    for b in bytes[1..len].iter_mut() {
+        let c = read_byte(br)?;
+        if c & 0xc0 != 0x80 {
+            return Err(invalid(c));
+        }
+        *b = c;
+    }
+    core::str::from_utf8(&bytes[..len])
+        .ok()
+        .and_then(|s| s.chars().next())
+        .ok_or_else(|| invalid(first))
+}
+
+// Decodes one `\uXXXX` escape, combining a high surrogate with the low
+// surrogate that must immediately follow it, and errors on any surrogate
+// left unpaired.
+fn read_unicode_escape<R: BufRead + ?Sized>(br: &mut R) -> Result<char> {
+    let cp = read_hex4(br)?;
+    if (0xdc00..=0xdfff).contains(&cp) {
+        return Err(unpaired_surrogate());
+    }
+    if (0xd800..=0xdbff).contains(&cp) {
+        if read_byte(br)? != b'\\' || read_byte(br)? != b'u' {
+            return Err(unpaired_surrogate());
+        }
+        let low = read_hex4(br)?;
+        if !(0xdc00..=0xdfff).contains(&low) {
+            return Err(unpaired_surrogate());
+        }
+        let c = 0x10000 + ((cp - 0xd800) << 10) + (low - 0xdc00);
+        return Ok(char::from_u32(c).unwrap());
+    }
+    Ok(char::from_u32(cp).unwrap())
+}
+
+// Writes `ch` as valid JSON string content: the characters JSON requires
+// to be escaped (`"`, `\`, and the C0 control range) always are, regardless
+// of `ascii`; everything else is written as literal UTF-8, or, in ASCII
+// mode, as one `\uXXXX` escape (two for an astral code point, split into a
+// surrogate pair) so the output never contains a non-ASCII byte.
+fn write_char<W: Write + ?Sized>(w: &mut W, ch: char, ascii: bool) -> Result<()> {
+    match ch {
+        '"' => w.write_all(b"\\\""),
+        '\\' => w.write_all(b"\\\\"),
+        '\u{8}' => w.write_all(b"\\b"),
+        '\u{c}' => w.write_all(b"\\f"),
+        '\n' => w.write_all(b"\\n"),
+        '\r' => w.write_all(b"\\r"),
+        '\t' => w.write_all(b"\\t"),
+        c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32),
+        c if ascii && !c.is_ascii() => {
+            let mut units = [0u16; 2];
+            for u in c.encode_utf16(&mut units) {
+                write!(w, "\\u{:04x}", u)?;
             }
-            i = 0;
+            Ok(())
         }
-        let c = buf[i];
-        i += 1;
-        if c == b'"' && prev != b'\\' {
-            break;
-        } else if c == b'\\' && prev == b'\\' {
-            prev = BAD_CHAR;
-        } else {
-            prev = c;
+        c => {
+            let mut buf = [0u8; 4];
+            w.write_all(c.encode_utf8(&mut buf).as_bytes())
         }
     }
-    w.write_all(&buf[..i])?;
-    br.consume(i);
-    Ok(())
 }
 
-fn write_number(w: &mut dyn Write, br: &mut BufReader<&mut dyn Read>) -> Result<()> {
+fn write_string<W: Write + ?Sized, R: BufRead + ?Sized>(
+    w: &mut W,
+    br: &mut R,
+    ascii: bool,
+) -> Result<()> {
+    let q = read_byte(br)?;
+    assert!(q == b'"');
+    w.write_all(&[q])?;
+    loop {
+        let c = read_byte(br)?;
+        match c {
+            b'"' => {
+                w.write_all(b"\"")?;
+                return Ok(());
+            }
+            b'\\' => {
+                let e = read_byte(br)?;
+                match e {
+                    b'u' => write_char(w, read_unicode_escape(br)?, ascii)?,
+                    b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => {
+                        w.write_all(&[b'\\', e])?
+                    }
+                    _ => return Err(invalid(e)),
+                }
+            }
+            c if c < 0x80 => write_char(w, c as char, ascii)?,
+            c => write_char(w, read_utf8_char(br, c)?, ascii)?,
+        }
+    }
+}
+
+fn write_number<W: Write + ?Sized, R: BufRead + ?Sized>(w: &mut W, br: &mut R) -> Result<()> {
     let mut buf = br.fill_buf()?;
     let mut i = 1usize;
     loop {
@@ -91,9 +395,9 @@ fn write_number(w: &mut dyn Write, br: &mut BufReader<&mut dyn Read>) -> Result<
     Ok(())
 }
 
-fn write_expected(
-    w: &mut dyn Write,
-    br: &mut BufReader<&mut dyn Read>,
+fn write_expected<W: Write + ?Sized, R: BufRead + ?Sized>(
+    w: &mut W,
+    br: &mut R,
     expect: &[u8],
 ) -> Result<()> {
     const MAX_ID_LEN: usize = 5;
@@ -103,18 +407,204 @@ fn write_expected(
     if expect != &buf[..expect.len()] {
         for (i, &c) in expect.iter().enumerate() {
             if c != buf[i] {
-                return Err(error_msg(format!("invalid input: {}", buf[i] as char)));
+                return Err(invalid(buf[i]));
             }
         }
     }
     w.write_all(expect)
 }
 
+struct NullSink;
+
+impl Write for NullSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Wraps a `BufRead` to track how many bytes have been consumed, so `Tokens`
+// can report the byte offsets of each token without every scanning helper
+// (`skip_whitespace`, `write_string`, ...) having to know about offsets.
+struct Counting<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counting<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.pos += amt as u64;
+    }
+}
+
+/// Byte offsets of a token in the input stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// One unit of JSON syntax. `Key`, `Str` and `Number` carry no payload of
+/// their own: `Tokens::next` writes their text into the buffer the caller
+/// supplies instead. Numbers are copied verbatim; keys and strings are
+/// written as `write_string` produces them, with `\uXXXX` escapes decoded
+/// (and, in ASCII mode, non-ASCII characters re-escaped), so they can differ
+/// from the input bytes their `Span` covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Token {
+    BeginObject,
+    EndObject,
+    BeginArray,
+    EndArray,
+    Key,
+    Str,
+    Number,
+    Bool(bool),
+    Null,
+}
+
+/// A pull-based JSON tokenizer, built on the same scanning helpers
+/// `format_json` uses. Unlike `format_json` it does no formatting of its
+/// own: each call to `next` advances past exactly one token and writes its
+/// text (a key, a string, a number, or the `true`/`false`/`null` literal)
+/// into `buf`.
+pub struct Tokens<R> {
+    br: Counting<R>,
+    stack: Vec<State>,
+    ascii: bool,
+}
+
+impl<R: BufRead> Tokens<R> {
+    pub fn new(br: R, ascii: bool) -> Self {
+        Self {
+            br: Counting { inner: br, pos: 0 },
+            stack: vec![State::Value],
+            ascii,
+        }
+    }
+
+    pub fn next<W: Write + ?Sized>(&mut self, buf: &mut W) -> Result<Option<(Token, Span)>> {
+        loop {
+            let state = match self.stack.pop() {
+                Some(state) => state,
+                None => return Ok(None),
+            };
+            skip_whitespace(&mut self.br)?;
+            match state {
+                State::Value => match peek_char(&mut self.br) {
+                    c if c == b'{' || c == b'[' => {
+                        let start = self.br.pos;
+                        self.br.consume(1);
+                        self.stack.push(if c == b'{' {
+                            State::Object
+                        } else {
+                            State::Array
+                        });
+                        let token = if c == b'{' {
+                            Token::BeginObject
+                        } else {
+                            Token::BeginArray
+                        };
+                        return Ok(Some((token, Span { start, end: self.br.pos })));
+                    }
+                    b'"' => {
+                        let start = self.br.pos;
+                        write_string(buf, &mut self.br, self.ascii)?;
+                        return Ok(Some((Token::Str, Span { start, end: self.br.pos })));
+                    }
+                    c if c.is_ascii_digit() || c == b'-' => {
+                        let start = self.br.pos;
+                        write_number(buf, &mut self.br)?;
+                        return Ok(Some((Token::Number, Span { start, end: self.br.pos })));
+                    }
+                    b'n' => {
+                        let start = self.br.pos;
+                        write_expected(buf, &mut self.br, b"null")?;
+                        return Ok(Some((Token::Null, Span { start, end: self.br.pos })));
+                    }
+                    b't' => {
+                        let start = self.br.pos;
+                        write_expected(buf, &mut self.br, b"true")?;
+                        return Ok(Some((Token::Bool(true), Span { start, end: self.br.pos })));
+                    }
+                    b'f' => {
+                        let start = self.br.pos;
+                        write_expected(buf, &mut self.br, b"false")?;
+                        return Ok(Some((Token::Bool(false), Span { start, end: self.br.pos })));
+                    }
+                    c => return Err(unexpected(c)),
+                },
+                State::Pair | State::Elem => {
+                    let c = peek_char(&mut self.br);
+                    if c == b',' {
+                        self.br.consume(1);
+                    } else if state == State::Pair && c != b'}' || state == State::Elem && c != b']'
+                    {
+                        return Err(unexpected(c));
+                    }
+                    self.stack.push(if state == State::Pair {
+                        State::Object
+                    } else {
+                        State::Array
+                    });
+                }
+                State::Object => match peek_char(&mut self.br) {
+                    b'"' => {
+                        let start = self.br.pos;
+                        write_string(buf, &mut self.br, self.ascii)?;
+                        let end = self.br.pos;
+                        skip_whitespace(&mut self.br)?;
+                        write_expected(&mut NullSink, &mut self.br, b":")?;
+                        self.stack.push(State::Pair);
+                        self.stack.push(State::Value);
+                        return Ok(Some((Token::Key, Span { start, end })));
+                    }
+                    b'}' => {
+                        let start = self.br.pos;
+                        self.br.consume(1);
+                        return Ok(Some((Token::EndObject, Span { start, end: self.br.pos })));
+                    }
+                    c => return Err(unexpected(c)),
+                },
+                State::Array => match peek_char(&mut self.br) {
+                    b']' => {
+                        let start = self.br.pos;
+                        self.br.consume(1);
+                        return Ok(Some((Token::EndArray, Span { start, end: self.br.pos })));
+                    }
+                    _ => {
+                        self.stack.push(State::Elem);
+                        self.stack.push(State::Value);
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 pub struct Indent {
     one_tab: String,
     prefix: String,
 }
 
+#[cfg(feature = "std")]
 impl Indent {
     pub fn new(width: usize) -> Self {
         Self {
@@ -131,93 +621,181 @@ impl Indent {
         self.prefix.truncate(self.prefix.len() - self.one_tab.len());
     }
 
-    fn write_to(&self, w: &mut dyn Write) -> Result<()> {
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
         w.write_all(self.prefix.as_bytes())
     }
 }
 
-pub fn format_json(
-    w: &mut dyn Write,
-    br: &mut BufReader<&mut dyn Read>,
+// Without an allocator there is no `String` to grow, so `no_std` builds fall
+// back to a fixed-capacity prefix buffer sized for reasonably nested
+// firmware payloads; deeper input is clamped rather than rejected.
+#[cfg(not(feature = "std"))]
+const INDENT_MAX_WIDTH: usize = 8;
+#[cfg(not(feature = "std"))]
+const INDENT_MAX_PREFIX: usize = 256;
+
+#[cfg(not(feature = "std"))]
+pub struct Indent {
+    tab_len: usize,
+    prefix: [u8; INDENT_MAX_PREFIX],
+    prefix_len: usize,
+}
+
+#[cfg(not(feature = "std"))]
+impl Indent {
+    pub fn new(width: usize) -> Self {
+        Self {
+            tab_len: width.min(INDENT_MAX_WIDTH),
+            prefix: [0u8; INDENT_MAX_PREFIX],
+            prefix_len: 0,
+        }
+    }
+
+    fn push(&mut self) {
+        let end = (self.prefix_len + self.tab_len).min(INDENT_MAX_PREFIX);
+        for b in self.prefix[self.prefix_len..end].iter_mut() {
+            *b = b' ';
+        }
+        self.prefix_len = end;
+    }
+
+    fn pop(&mut self) {
+        self.prefix_len = self.prefix_len.saturating_sub(self.tab_len);
+    }
+
+    fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.prefix[..self.prefix_len])
+    }
+}
+
+// Tracks, for the container `format_json` is currently inside, whether a
+// leading `,\n` is owed before the next sibling (`pending`), and, for an
+// object, whether we're between a key and its value (`awaiting_value`, which
+// suppresses that separator since "key: value" share a line).
+enum Frame {
+    Array { pending: bool },
+    Object { pending: bool, awaiting_value: bool },
+}
+
+fn awaiting_value(frames: &[Frame]) -> bool {
+    matches!(
+        frames.last(),
+        Some(Frame::Object {
+            awaiting_value: true,
+            ..
+        })
+    )
+}
+
+fn mark_value_written(frames: &mut [Frame]) {
+    match frames.last_mut() {
+        Some(Frame::Array { pending }) => *pending = true,
+        Some(Frame::Object { pending, .. }) => *pending = true,
+        None => {}
+    }
+}
+
+fn write_sibling_prefix<W: Write + ?Sized>(
+    w: &mut W,
+    indent: &Indent,
+    frames: &mut [Frame],
+) -> Result<()> {
+    let pending = match frames.last_mut() {
+        Some(Frame::Array { pending }) => pending,
+        Some(Frame::Object { pending, .. }) => pending,
+        None => return indent.write_to(w),
+    };
+    if *pending {
+        w.write_all(b",\n")?;
+    }
+    indent.write_to(w)
+}
+
+pub fn format_json<W: Write + ?Sized, R: BufRead + ?Sized>(
+    w: &mut W,
+    br: &mut R,
     indent: &mut Indent,
+    ascii: bool,
 ) -> Result<()> {
-    let mut stack = vec![State::Value];
-    while let Some(state) = stack.pop() {
-        skip_whitespace(br)?;
-        match state {
-            State::Value => match peek_char(br) {
-                c if c == b'{' || c == b'[' => {
-                    br.consume(1);
-                    w.write(&[c, b'\n'])?;
-                    indent.push();
-                    stack.push(if c == b'{' {
-                        State::Object
-                    } else {
-                        State::Array
-                    });
-                }
-                b'"' => write_string(w, br)?,
-                c if c.is_ascii_digit() || c == b'-' => write_number(w, br)?,
-                b'n' => write_expected(w, br, b"null")?,
-                b't' => write_expected(w, br, b"true")?,
-                b'f' => write_expected(w, br, b"false")?,
-                c => return Err(error_msg(format!("unexpected input: '{}'", c as char))),
-            },
-            State::Pair | State::Elem => {
-                let c = peek_char(br);
-                if c == b',' {
-                    br.consume(1);
-                    w.write(b",")?;
-                } else if state == State::Pair && c != b'}' || state == State::Elem && c != b']' {
-                    return Err(error_msg(format!("unexpected input: '{}'", c as char)));
+    let mut tokens = Tokens::new(br, ascii);
+    let mut frames: Vec<Frame> = vec![];
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        let (token, _) = match tokens.next(&mut buf)? {
+            Some(t) => t,
+            None => break,
+        };
+        match token {
+            Token::EndObject | Token::EndArray => {
+                let had_children = match frames.pop() {
+                    Some(Frame::Array { pending }) => pending,
+                    Some(Frame::Object { pending, .. }) => pending,
+                    None => false,
+                };
+                if had_children {
+                    w.write_all(b"\n")?;
                 }
-                w.write(b"\n")?;
-                stack.push(if state == State::Pair {
-                    State::Object
+                indent.pop();
+                indent.write_to(w)?;
+                w.write_all(if token == Token::EndObject {
+                    b"}"
                 } else {
-                    State::Array
-                });
+                    b"]"
+                })?;
+                mark_value_written(&mut frames);
+            }
+            Token::Key => {
+                write_sibling_prefix(w, indent, &mut frames)?;
+                w.write_all(&buf)?;
+                w.write_all(b": ")?;
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = true;
+                }
             }
-            State::Object => match peek_char(br) {
-                b'"' => {
-                    indent.write_to(w)?;
-                    write_string(w, br)?;
-                    skip_whitespace(br)?;
-                    write_expected(w, br, b":")?;
-                    w.write(b" ")?;
-                    stack.push(State::Pair);
-                    stack.push(State::Value);
+            Token::BeginObject | Token::BeginArray => {
+                if !awaiting_value(&frames) {
+                    write_sibling_prefix(w, indent, &mut frames)?;
                 }
-                b'}' => {
-                    br.consume(1);
-                    indent.pop();
-                    indent.write_to(w)?;
-                    w.write(b"}")?;
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = false;
                 }
-                c => return Err(error_msg(format!("unexpected input: '{}'", c as char))),
-            },
-            State::Array => match peek_char(br) {
-                b']' => {
-                    br.consume(1);
-                    indent.pop();
-                    indent.write_to(w)?;
-                    w.write(b"]")?;
+                w.write_all(if token == Token::BeginObject {
+                    b"{"
+                } else {
+                    b"["
+                })?;
+                w.write_all(b"\n")?;
+                indent.push();
+                frames.push(if token == Token::BeginObject {
+                    Frame::Object {
+                        pending: false,
+                        awaiting_value: false,
+                    }
+                } else {
+                    Frame::Array { pending: false }
+                });
+            }
+            Token::Str | Token::Number | Token::Bool(_) | Token::Null => {
+                if !awaiting_value(&frames) {
+                    write_sibling_prefix(w, indent, &mut frames)?;
                 }
-                _ => {
-                    indent.write_to(w)?;
-                    stack.push(State::Elem);
-                    stack.push(State::Value);
+                w.write_all(&buf)?;
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = false;
                 }
-            },
+                mark_value_written(&mut frames);
+            }
         }
     }
     Ok(())
 }
 
-pub fn format_json_fast(
-    w: &mut dyn Write,
-    br: &mut BufReader<&mut dyn Read>,
+pub fn format_json_fast<W: Write + ?Sized, R: BufRead + ?Sized>(
+    w: &mut W,
+    br: &mut R,
     indent: &mut Indent,
+    ascii: bool,
 ) -> Result<()> {
     let mut new_line = false;
     let mut buf = br.fill_buf()?;
@@ -236,7 +814,7 @@ pub fn format_json_fast(
         if c == b'}' || c == b']' {
             indent.pop();
             if !new_line {
-                w.write(b"\n")?;
+                w.write_all(b"\n")?;
                 indent.write_to(w)?;
             }
         }
@@ -247,26 +825,26 @@ pub fn format_json_fast(
         match c {
             b'"' => {
                 br.consume(i);
-                write_string(w, br)?;
+                write_string(w, br, ascii)?;
                 buf = br.fill_buf()?;
                 i = 0;
                 continue;
             }
             b'{' | b'[' => {
                 indent.push();
-                w.write(&[c, b'\n'])?;
+                w.write_all(&[c, b'\n'])?;
                 new_line = true;
             }
             b',' => {
-                w.write(b",\n")?;
+                w.write_all(b",\n")?;
                 new_line = true;
             }
             b':' => {
-                w.write(b": ")?;
+                w.write_all(b": ")?;
             }
             b'\t' | b' ' | b'\n' | b'\r' => {}
             _ => {
-                w.write(&[c])?;
+                w.write_all(&[c])?;
             }
         }
         i += 1;
@@ -274,12 +852,97 @@ pub fn format_json_fast(
     Ok(())
 }
 
+fn write_sibling_comma<W: Write + ?Sized>(w: &mut W, frames: &mut [Frame]) -> Result<()> {
+    let pending = match frames.last_mut() {
+        Some(Frame::Array { pending }) => pending,
+        Some(Frame::Object { pending, .. }) => pending,
+        None => return Ok(()),
+    };
+    if *pending {
+        w.write_all(b",")?;
+    }
+    Ok(())
+}
+
+/// The inverse of `format_json`: the canonical minimal form, with no
+/// insignificant whitespace at all. Shares `Tokens` (and so `write_string`,
+/// `write_number` and the `State` stack) with `format_json` for scanning and
+/// validation, it just never writes a separator beyond `,` and `:`.
+pub fn format_json_minify<W: Write + ?Sized, R: BufRead + ?Sized>(
+    w: &mut W,
+    br: &mut R,
+    ascii: bool,
+) -> Result<()> {
+    let mut tokens = Tokens::new(br, ascii);
+    let mut frames: Vec<Frame> = vec![];
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        let (token, _) = match tokens.next(&mut buf)? {
+            Some(t) => t,
+            None => break,
+        };
+        match token {
+            Token::EndObject | Token::EndArray => {
+                frames.pop();
+                w.write_all(if token == Token::EndObject {
+                    b"}"
+                } else {
+                    b"]"
+                })?;
+                mark_value_written(&mut frames);
+            }
+            Token::Key => {
+                write_sibling_comma(w, &mut frames)?;
+                w.write_all(&buf)?;
+                w.write_all(b":")?;
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = true;
+                }
+            }
+            Token::BeginObject | Token::BeginArray => {
+                if !awaiting_value(&frames) {
+                    write_sibling_comma(w, &mut frames)?;
+                }
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = false;
+                }
+                w.write_all(if token == Token::BeginObject {
+                    b"{"
+                } else {
+                    b"["
+                })?;
+                frames.push(if token == Token::BeginObject {
+                    Frame::Object {
+                        pending: false,
+                        awaiting_value: false,
+                    }
+                } else {
+                    Frame::Array { pending: false }
+                });
+            }
+            Token::Str | Token::Number | Token::Bool(_) | Token::Null => {
+                if !awaiting_value(&frames) {
+                    write_sibling_comma(w, &mut frames)?;
+                }
+                w.write_all(&buf)?;
+                if let Some(Frame::Object { awaiting_value, .. }) = frames.last_mut() {
+                    *awaiting_value = false;
+                }
+                mark_value_written(&mut frames);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::BufReader;
 
-    const TEST_CASE1_IN: &'static [u8] = br#"{ "a" : 1 , "b": "test \\", "c": false, "d": null, "e": 1.234e5, "f":  [ 1, 2  ] , "g"   : {},"h": [[[[]]]]}"#;
-    const TEST_CASE1_OUT: &'static [u8] = br#"{
+    const TEST_CASE1_IN: &[u8] = br#"{ "a" : 1 , "b": "test \\", "c": false, "d": null, "e": 1.234e5, "f":  [ 1, 2  ] , "g"   : {},"h": [[[[]]]]}"#;
+    const TEST_CASE1_OUT: &[u8] = br#"{
     "a": 1,
     "b": "test \\",
     "c": false,
@@ -308,7 +971,7 @@ mod tests {
         let r: &mut dyn Read = &mut &TEST_CASE1_IN[..];
         let mut br = BufReader::new(r);
         let mut indent = Indent::new(4);
-        format_json(&mut outbuf, &mut br, &mut indent).unwrap();
+        format_json(&mut outbuf, &mut br, &mut indent, false).unwrap();
         println!(">> {}", std::str::from_utf8(&outbuf).unwrap());
         assert_eq!(outbuf.as_slice(), TEST_CASE1_OUT);
     }
@@ -320,8 +983,141 @@ mod tests {
         let r: &mut dyn Read = &mut &TEST_CASE1_IN[..];
         let mut br = BufReader::new(r);
         let mut indent = Indent::new(4);
-        format_json_fast(&mut outbuf, &mut br, &mut indent).unwrap();
+        format_json_fast(&mut outbuf, &mut br, &mut indent, false).unwrap();
         println!(">> {}", std::str::from_utf8(&outbuf).unwrap());
         assert_eq!(outbuf.as_slice(), TEST_CASE1_OUT);
     }
+
+    const TEST_CASE2_IN: &str = r#"["café", "😀"]"#;
+
+    #[test]
+    fn test_unicode_escape_decode_and_surrogate_pair() {
+        let input = "[\"caf\\u00e9\", \"\\ud83d\\ude00\"]";
+        let mut outbuf = Vec::<u8>::new();
+        let r: &mut dyn Read = &mut input.as_bytes();
+        let mut br = BufReader::new(r);
+        let mut indent = Indent::new(4);
+        format_json(&mut outbuf, &mut br, &mut indent, false).unwrap();
+        let out = std::str::from_utf8(&outbuf).unwrap();
+        assert!(out.contains("caf\u{e9}"));
+        assert!(out.contains('\u{1f600}'));
+    }
+
+    #[test]
+    fn test_unpaired_surrogate_is_an_error() {
+        let input = r#"["\ud83d"]"#;
+        let mut outbuf = Vec::<u8>::new();
+        let r: &mut dyn Read = &mut input.as_bytes();
+        let mut br = BufReader::new(r);
+        let mut indent = Indent::new(4);
+        assert!(format_json(&mut outbuf, &mut br, &mut indent, false).is_err());
+    }
+
+    #[test]
+    fn test_ascii_mode_reescapes_non_ascii() {
+        let mut outbuf = Vec::<u8>::new();
+        let r: &mut dyn Read = &mut TEST_CASE2_IN.as_bytes();
+        let mut br = BufReader::new(r);
+        let mut indent = Indent::new(4);
+        format_json(&mut outbuf, &mut br, &mut indent, true).unwrap();
+        let out = std::str::from_utf8(&outbuf).unwrap();
+        assert!(out.is_ascii());
+        assert!(out.contains("caf\\u00e9"));
+        assert!(out.contains("\\ud83d\\ude00"));
+    }
+
+    #[test]
+    fn test_decoded_escapes_that_must_themselves_stay_escaped() {
+        // `\uXXXX` escapes that decode to characters JSON itself requires to
+        // be escaped must come back out re-escaped, not written raw, in both
+        // the default and `--ascii` modes.
+        let cases = [
+            (r#"["\u0022"]"#, r#""\"""#),
+            (r#"["\u005c"]"#, r#""\\""#),
+            (r#"["\u000a"]"#, r#""\n""#),
+            (r#"["\u0000"]"#, r#""\u0000""#),
+        ];
+        for (input, expect) in cases {
+            for ascii in [false, true] {
+                let mut outbuf = Vec::<u8>::new();
+                let r: &mut dyn Read = &mut input.as_bytes();
+                let mut br = BufReader::new(r);
+                let mut indent = Indent::new(4);
+                format_json(&mut outbuf, &mut br, &mut indent, ascii).unwrap();
+                let out = std::str::from_utf8(&outbuf).unwrap();
+                assert!(
+                    out.contains(expect),
+                    "input {:?} (ascii={}) produced {:?}, expected to contain {:?}",
+                    input,
+                    ascii,
+                    out,
+                    expect
+                );
+                // whatever we produced must itself still be valid, re-parseable JSON.
+                let mut reparsed = Vec::<u8>::new();
+                let r2: &mut dyn Read = &mut out.as_bytes();
+                let mut br2 = BufReader::new(r2);
+                let mut indent2 = Indent::new(4);
+                format_json(&mut reparsed, &mut br2, &mut indent2, ascii).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokens_yields_keys_and_values_with_spans() {
+        let r: &mut dyn Read = &mut &TEST_CASE1_IN[..];
+        let br = BufReader::new(r);
+        let mut tokens = Tokens::new(br, false);
+        let mut buf = Vec::<u8>::new();
+
+        buf.clear();
+        let (token, span) = tokens.next(&mut buf).unwrap().unwrap();
+        assert_eq!(token, Token::BeginObject);
+        assert_eq!(span, Span { start: 0, end: 1 });
+
+        buf.clear();
+        let (token, _) = tokens.next(&mut buf).unwrap().unwrap();
+        assert_eq!(token, Token::Key);
+        assert_eq!(buf, br#""a""#);
+
+        buf.clear();
+        let (token, _) = tokens.next(&mut buf).unwrap().unwrap();
+        assert_eq!(token, Token::Number);
+        assert_eq!(buf, b"1");
+    }
+
+    #[test]
+    fn test_key_span_excludes_trailing_whitespace_and_colon() {
+        // The `Key` token's span must cover only the key's own bytes, not the
+        // whitespace/colon that `Tokens::next` keeps scanning past to queue up
+        // the value -- otherwise callers slicing the input by span see a key
+        // string with trailing garbage appended.
+        let input = br#"{"key"   :   1}"#;
+        let r: &mut dyn Read = &mut &input[..];
+        let br = BufReader::new(r);
+        let mut tokens = Tokens::new(br, false);
+        let mut buf = Vec::<u8>::new();
+
+        buf.clear();
+        tokens.next(&mut buf).unwrap().unwrap(); // BeginObject
+
+        buf.clear();
+        let (token, span) = tokens.next(&mut buf).unwrap().unwrap();
+        assert_eq!(token, Token::Key);
+        assert_eq!(buf, br#""key""#);
+        assert_eq!(span, Span { start: 1, end: 1 + buf.len() as u64 });
+        assert_eq!(&input[span.start as usize..span.end as usize], buf.as_slice());
+    }
+
+    #[test]
+    fn test_format_json_minify() {
+        let mut outbuf = Vec::<u8>::new();
+        let r: &mut dyn Read = &mut &TEST_CASE1_IN[..];
+        let mut br = BufReader::new(r);
+        format_json_minify(&mut outbuf, &mut br, false).unwrap();
+        assert_eq!(
+            outbuf.as_slice(),
+            br#"{"a":1,"b":"test \\","c":false,"d":null,"e":1.234e5,"f":[1,2],"g":{},"h":[[[[]]]]}"#
+        );
+    }
 }
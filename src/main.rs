@@ -16,6 +16,8 @@ fn show_help(code: i32) -> ! {
     println!("  -i <width> indent width");
     println!("  -w         write back");
     println!("  -f         fast");
+    println!("  -c         compact (minify), no indentation or newlines");
+    println!("  --ascii    escape non-ASCII characters as \\uXXXX");
     process::exit(code)
 }
 
@@ -23,6 +25,8 @@ fn main() {
     let mut indent = 2usize;
     let mut write_back = false;
     let mut fast = false;
+    let mut compact = false;
+    let mut ascii = false;
     let mut input = None;
     let mut args = env::args();
     args.next();
@@ -37,6 +41,8 @@ fn main() {
             }
             "-f" => fast = true,
             "-w" => write_back = true,
+            "-c" | "--compact" => compact = true,
+            "--ascii" => ascii = true,
             "-h" => show_help(0),
             _ => {
                 input = Some(arg);
@@ -66,11 +72,15 @@ fn main() {
     } else {
         &mut stdout
     };
-    let mut indent = Indent::new(indent);
-    let res = if fast {
-        format_json_fast(w, &mut br, &mut indent)
+    let res = if compact {
+        format_json_minify(w, &mut br, ascii)
     } else {
-        format_json(w, &mut br, &mut indent)
+        let mut indent = Indent::new(indent);
+        if fast {
+            format_json_fast(w, &mut br, &mut indent, ascii)
+        } else {
+            format_json(w, &mut br, &mut indent, ascii)
+        }
     };
     if let Err(e) = res {
         fatal(e.to_string());